@@ -1,23 +1,34 @@
 use clap::Parser;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::WalkBuilder;
 use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
-use reqwest::blocking::Client;
+use rand::Rng;
+use reqwest::Client;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::Path;
-use std::sync::mpsc::channel;
-use std::thread;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::task::JoinHandle;
 
-#[derive(Parser)]
+#[derive(Parser, Clone)]
 #[command(name = "deployer")]
 #[command(about = "Deploy and watch JavaScript files for aiwebengine")]
 struct Args {
-    /// URI for the script (e.g., https://example.com/my-script)
+    /// Base URI for the script(s), e.g. https://example.com/my-script.
+    /// With --dir, this is the prefix each file's relative path is appended to.
     #[arg(short, long)]
     uri: String,
 
-    /// Path to the JavaScript file to deploy
+    /// Path to a single JavaScript file to deploy
     #[arg(short, long)]
-    file: String,
+    file: Option<String>,
+
+    /// Path to a directory to recursively deploy; every non-ignored .js file
+    /// under it is deployed to `{uri}/{relative/path/without/extension}`
+    #[arg(short, long)]
+    dir: Option<String>,
 
     /// Server URL (default: http://localhost:4000)
     #[arg(short, long, default_value = "http://localhost:4000")]
@@ -26,57 +37,385 @@ struct Args {
     /// Watch for file changes (default: true)
     #[arg(short, long, default_value = "true")]
     watch: bool,
+
+    /// Debounce window in milliseconds: wait for this long without new events before redeploying
+    #[arg(long, default_value = "500")]
+    debounce_ms: u64,
+
+    /// Maximum retry attempts for a deploy on a transport error or a retryable
+    /// status (408, 429, 5xx), using exponential backoff with jitter
+    #[arg(long, default_value = "5")]
+    max_retries: u32,
+
+    /// Shell command to run before each deploy (e.g. "esbuild src/index.ts --bundle").
+    /// On a non-zero exit, its stderr is printed and that deploy cycle is skipped.
+    #[arg(long)]
+    build: Option<String>,
+
+    /// Path to the build's output artifact; when --build is set, its contents
+    /// are deployed instead of the raw watched file
+    #[arg(long)]
+    artifact: Option<String>,
+
+    /// Per-request timeout in seconds; a deploy attempt that takes longer is
+    /// cancelled and counts as a failed attempt for retry purposes
+    #[arg(long, default_value = "30")]
+    timeout_secs: u64,
+}
+
+/// What `deployer` is deploying: either one explicit file, or a directory tree
+/// whose files are mapped to URIs derived from their path relative to the root.
+enum Target {
+    File {
+        path: PathBuf,
+        uri: String,
+    },
+    Dir {
+        root: PathBuf,
+        base_uri: String,
+        ignore_rules: IgnoreRules,
+    },
+}
+
+/// The `node_modules`/`target`/`.deployignore` exclusion rules, in two forms
+/// built from the same pattern list: an `Override` for `WalkBuilder` to prune
+/// whole directories during the initial walk, and a `Gitignore` matcher for
+/// per-path checks during watch, which (unlike `Override::matched`) also
+/// checks ancestors so a file inside an ignored directory is caught too.
+struct IgnoreRules {
+    walk_overrides: Override,
+    matcher: Gitignore,
+}
+
+impl IgnoreRules {
+    fn build(root: &Path) -> Result<IgnoreRules, Box<dyn std::error::Error>> {
+        let mut patterns = vec!["node_modules".to_string(), "target".to_string()];
+        if let Ok(contents) = fs::read_to_string(root.join(".deployignore")) {
+            for line in contents.lines() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                patterns.push(line.to_string());
+            }
+        }
+
+        let mut override_builder = OverrideBuilder::new(root);
+        let mut gitignore_builder = GitignoreBuilder::new(root);
+        for pattern in &patterns {
+            override_builder.add(&format!("!{}", pattern))?;
+            gitignore_builder.add_line(None, pattern)?;
+        }
+
+        Ok(IgnoreRules {
+            walk_overrides: override_builder.build()?,
+            matcher: gitignore_builder.build()?,
+        })
+    }
+
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.matcher.matched_path_or_any_parents(path, is_dir).is_ignore()
+    }
+}
+
+impl Target {
+    fn from_args(args: &Args) -> Result<Target, Box<dyn std::error::Error>> {
+        match (&args.file, &args.dir) {
+            (Some(_), Some(_)) => Err("Specify either --file or --dir, not both".into()),
+            (None, None) => Err("Specify either --file or --dir".into()),
+            (Some(file), None) => {
+                let path = Path::new(file);
+                if !path.exists() {
+                    return Err(format!("File '{}' does not exist", file).into());
+                }
+                Ok(Target::File {
+                    path: path.canonicalize()?,
+                    uri: args.uri.clone(),
+                })
+            }
+            (None, Some(dir)) => {
+                let root = PathBuf::from(dir);
+                if !root.is_dir() {
+                    return Err(format!("Directory '{}' does not exist", dir).into());
+                }
+                let root = root.canonicalize()?;
+                Ok(Target::Dir {
+                    ignore_rules: IgnoreRules::build(&root)?,
+                    root,
+                    base_uri: args.uri.clone(),
+                })
+            }
+        }
+    }
+
+    /// Path to hand to the watcher, and whether it needs to watch subdirectories.
+    fn watch_path(&self) -> (&Path, RecursiveMode) {
+        match self {
+            Target::File { path, .. } => (path, RecursiveMode::NonRecursive),
+            Target::Dir { root, .. } => (root, RecursiveMode::Recursive),
+        }
+    }
+
+    /// All (uri, file_path) pairs that should be deployed right now.
+    fn initial_deploys(&self) -> Vec<(String, PathBuf)> {
+        match self {
+            Target::File { path, uri } => vec![(uri.clone(), path.clone())],
+            Target::Dir {
+                root,
+                base_uri,
+                ignore_rules,
+            } => {
+                let mut walker = WalkBuilder::new(root);
+                walker.overrides(ignore_rules.walk_overrides.clone());
+                walker
+                    .build()
+                    .filter_map(Result::ok)
+                    .map(|entry| entry.into_path())
+                    .filter(|path| is_deployable_js_file(path))
+                    .map(|path| (uri_for_path(base_uri, root, &path), path))
+                    .collect()
+            }
+        }
+    }
+
+    /// Resolve a changed path into a (uri, file_path) pair to redeploy, or
+    /// `None` if the path isn't a script this target cares about. The event
+    /// path is canonicalized before comparing, since notify doesn't guarantee
+    /// it comes back in the same relative/absolute form as the watched path.
+    fn resolve_change(&self, path: &Path) -> Option<(String, PathBuf)> {
+        let path = canonical_or(path);
+        match self {
+            Target::File { path: watched, uri } => {
+                (path == *watched).then(|| (uri.clone(), watched.clone()))
+            }
+            Target::Dir {
+                root,
+                base_uri,
+                ignore_rules,
+            } => {
+                if !is_deployable_js_file(&path) || ignore_rules.is_ignored(&path, false) {
+                    return None;
+                }
+                Some((uri_for_path(base_uri, root, &path), path))
+            }
+        }
+    }
+}
+
+/// Canonicalize `path`, falling back to it unchanged if that fails (e.g. the
+/// file was already removed or renamed away by the time we look at it).
+fn canonical_or(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn is_deployable_js_file(path: &Path) -> bool {
+    path.is_file() && path.extension().map_or(false, |ext| ext == "js")
+}
+
+/// Map `src/foo/bar.js` under `root` to `{base_uri}/foo/bar`.
+fn uri_for_path(base_uri: &str, root: &Path, file: &Path) -> String {
+    let relative = file.strip_prefix(root).unwrap_or(file).with_extension("");
+    let relative = relative
+        .to_string_lossy()
+        .replace(std::path::MAIN_SEPARATOR, "/");
+    format!("{}/{}", base_uri.trim_end_matches('/'), relative)
+}
+
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(16);
+
+fn is_retryable_status(status: reqwest::StatusCode) -> bool {
+    matches!(status.as_u16(), 408 | 429) || status.is_server_error()
+}
+
+/// Add up to 250ms of random jitter so concurrent retries don't all line up.
+fn with_jitter(delay: Duration) -> Duration {
+    delay + Duration::from_millis(rand::thread_rng().gen_range(0..=250))
 }
 
-fn deploy_script(
+/// Deploy `file_path` to `uri`, retrying on a transport error or a retryable
+/// status with exponential backoff and jitter. Each attempt is bounded by
+/// `request_timeout`; an attempt that exceeds it is cancelled and treated the
+/// same as a transport error for retry purposes.
+async fn deploy_script(
     client: &Client,
     server_url: &str,
     uri: &str,
-    file_path: &str,
+    file_path: &Path,
+    max_retries: u32,
+    request_timeout: Duration,
 ) -> Result<(), Box<dyn std::error::Error>> {
     // Read the file content
-    let content = fs::read_to_string(file_path)?;
+    let content = tokio::fs::read_to_string(file_path).await?;
 
     // Construct the API URL
     let api_url = format!("{}/api/scripts/{}", server_url, uri);
 
-    println!("🚀 Deploying {} to {}", file_path, api_url);
+    println!("🚀 Deploying {} to {}", file_path.display(), api_url);
 
-    // Send the POST request with the file content as body
-    let response = client.post(&api_url).body(content).send()?;
+    let mut delay = INITIAL_RETRY_DELAY;
+
+    for attempt_index in 0..=max_retries {
+        let is_last_attempt = attempt_index == max_retries;
+
+        // Bound the whole attempt -- request plus reading a failed response's
+        // body -- so a stalled response can't hang past `request_timeout`.
+        let attempt = tokio::time::timeout(request_timeout, async {
+            let response = client.post(&api_url).body(content.clone()).send().await?;
+            let status = response.status();
+            if status.is_success() {
+                return Ok((status, None, String::new()));
+            }
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<u64>().ok())
+                .map(Duration::from_secs);
+            let error_text = response.text().await.unwrap_or_default();
+            Ok::<_, reqwest::Error>((status, retry_after, error_text))
+        })
+        .await;
+
+        let (reason, wait) = match attempt {
+            Ok(Ok((status, _, _))) if status.is_success() => {
+                println!("✅ Successfully deployed script: {}", uri);
+                return Ok(());
+            }
+            Ok(Ok((status, retry_after, error_text))) => {
+                if !is_retryable_status(status) || is_last_attempt {
+                    println!("❌ Failed to deploy script: {} (Status: {})", uri, status);
+                    if !error_text.is_empty() {
+                        println!("Error details: {}", error_text);
+                    }
+                    return Err(format!("deploy failed with status {}", status).into());
+                }
+                (format!("Status: {}", status), retry_after.unwrap_or(delay))
+            }
+            Ok(Err(e)) => {
+                if is_last_attempt {
+                    return Err(e.into());
+                }
+                (e.to_string(), delay)
+            }
+            Err(_elapsed) => {
+                if is_last_attempt {
+                    return Err(format!(
+                        "deploy request timed out after {:?}",
+                        request_timeout
+                    )
+                    .into());
+                }
+                (format!("timed out after {:?}", request_timeout), delay)
+            }
+        };
 
-    if response.status().is_success() {
-        println!("✅ Successfully deployed script: {}", uri);
-    } else {
         println!(
-            "❌ Failed to deploy script: {} (Status: {})",
-            uri,
-            response.status()
+            "⚠️  Deploy attempt {} failed ({}), retrying in {:?}...",
+            attempt_index + 1,
+            reason,
+            wait
         );
-        if let Ok(error_text) = response.text() {
-            println!("Error details: {}", error_text);
+        tokio::time::sleep(with_jitter(wait)).await;
+        delay = (delay * 2).min(MAX_RETRY_DELAY);
+    }
+
+    unreachable!("loop always returns on its last iteration")
+}
+
+/// Run the configured `--build` command, returning its stderr on a non-zero exit.
+async fn run_build(command: &str) -> Result<(), String> {
+    let output = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .output()
+        .await
+        .map_err(|e| format!("failed to run build command: {}", e))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).into_owned())
+    }
+}
+
+/// Run the pre-deploy build hook (if configured) and deploy its artifact, or
+/// the raw watched file when there's no build hook. Skips the deploy (without
+/// error) if the build command fails, so stale code never gets pushed.
+async fn deploy_with_build(
+    client: &Client,
+    args: &Args,
+    uri: &str,
+    file_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(build_cmd) = &args.build {
+        if let Err(stderr) = run_build(build_cmd).await {
+            eprintln!("❌ Build failed, skipping deploy:\n{}", stderr);
+            return Ok(());
         }
     }
 
+    // `validate_args` guarantees --artifact is set whenever --build is, so
+    // this only uses the artifact for a build-hook deploy.
+    let deploy_path = match &args.artifact {
+        Some(artifact) if args.build.is_some() => Path::new(artifact),
+        _ => file_path,
+    };
+
+    deploy_script(
+        client,
+        &args.server,
+        uri,
+        deploy_path,
+        args.max_retries,
+        Duration::from_secs(args.timeout_secs),
+    )
+    .await
+}
+
+/// Reject flag combinations `deploy_with_build` can't sanely handle: `--dir`
+/// has many files sharing one `--build`/`--artifact` pair, which would re-run
+/// the build per file and push the same bundle to every file's distinct URI;
+/// and `--build` without `--artifact` would silently fall back to deploying
+/// the raw, unbuilt source instead of the build's output.
+fn validate_args(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    if args.build.is_some() && args.dir.is_some() {
+        return Err(
+            "--build is not supported with --dir; use --file with a single bundle entry point"
+                .into(),
+        );
+    }
+    if args.build.is_some() && args.artifact.is_none() {
+        return Err("--artifact is required when --build is set".into());
+    }
     Ok(())
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Arc::new(Args::parse());
 
-    // Validate that the file exists
-    if !Path::new(&args.file).exists() {
-        eprintln!("❌ Error: File '{}' does not exist", args.file);
+    if let Err(e) = validate_args(&args) {
+        eprintln!("❌ Error: {}", e);
         std::process::exit(1);
     }
 
-    // Create HTTP client
-    let client = Client::builder().timeout(Duration::from_secs(30)).build()?;
+    let target = match Target::from_args(&args) {
+        Ok(target) => target,
+        Err(e) => {
+            eprintln!("❌ Error: {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    // Initial deployment
-    if let Err(e) = deploy_script(&client, &args.server, &args.uri, &args.file) {
-        eprintln!("❌ Initial deployment failed: {}", e);
-        std::process::exit(1);
+    let client = Client::new();
+
+    // Initial deployment: a single file, or the whole non-ignored tree under --dir
+    for (uri, file_path) in target.initial_deploys() {
+        if let Err(e) = deploy_with_build(&client, &args, &uri, &file_path).await {
+            eprintln!("❌ Initial deployment failed: {}", e);
+            std::process::exit(1);
+        }
     }
 
     if !args.watch {
@@ -86,42 +425,89 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     println!("👀 Watching for file changes... (Press Ctrl+C to stop)");
 
-    // Create a channel for file system events
-    let (tx, rx) = channel();
+    // Create a channel for file system events; the watcher's callback runs on
+    // its own thread, so forward events into an async channel for the main loop.
+    let (event_tx, mut event_rx) = tokio::sync::mpsc::unbounded_channel();
 
     // Create a file watcher
-    let mut watcher = RecommendedWatcher::new(tx, Config::default())?;
+    let mut watcher = RecommendedWatcher::new(
+        move |event| {
+            let _ = event_tx.send(event);
+        },
+        Config::default(),
+    )?;
+
+    // Watch the file or directory
+    let (watch_path, recursive_mode) = target.watch_path();
+    watcher.watch(watch_path, recursive_mode)?;
+
+    let cooldown = Duration::from_millis(args.debounce_ms);
+    let mut pending: HashSet<PathBuf> = HashSet::new();
+    // Deploys currently in flight, so a newer change to the same file can
+    // cancel the stale request instead of waiting behind it.
+    let mut in_flight: HashMap<PathBuf, JoinHandle<()>> = HashMap::new();
 
-    // Watch the file
-    watcher.watch(Path::new(&args.file), RecursiveMode::NonRecursive)?;
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
 
-    // Main event loop
+    // Main event loop: coalesce bursts of filesystem events into a single deploy
+    // per changed file. After the first event in a burst, keep draining with
+    // a timeout until the cooldown elapses with no new events, then deploy
+    // every file that changed.
     loop {
-        match rx.recv() {
-            Ok(event) => {
-                match event {
-                    Ok(event) => {
-                        // Check if it's a write event (file modified)
+        tokio::select! {
+            _ = &mut ctrl_c => {
+                println!("\n🛑 Ctrl+C received, cancelling in-flight deploys...");
+                for (_, handle) in in_flight.drain() {
+                    handle.abort();
+                }
+                drop(watcher);
+                break;
+            }
+            recv = tokio::time::timeout(cooldown, event_rx.recv()) => {
+                match recv {
+                    Ok(Some(Ok(event))) => {
                         if event.kind.is_modify() || event.kind.is_create() {
-                            println!("📝 File changed, redeploying...");
+                            for path in event.paths {
+                                if target.resolve_change(&path).is_some() {
+                                    pending.insert(path);
+                                }
+                            }
+                        }
+                    }
+                    Ok(Some(Err(e))) => eprintln!("❌ Watch error: {:?}", e),
+                    Ok(None) => {
+                        eprintln!("❌ Channel error: watcher disconnected");
+                        break;
+                    }
+                    Err(_elapsed) => {
+                        // Drop handles for deploys that already finished so the map
+                        // doesn't grow with every distinct file touched over the session.
+                        in_flight.retain(|_, handle| !handle.is_finished());
 
-                            // Small delay to ensure file is fully written
-                            thread::sleep(Duration::from_millis(100));
+                        for path in pending.drain() {
+                            if let Some((uri, file_path)) = target.resolve_change(&path) {
+                                // A newer change to this file arrived; abort the stale upload.
+                                if let Some(handle) = in_flight.remove(&file_path) {
+                                    handle.abort();
+                                }
 
-                            if let Err(e) =
-                                deploy_script(&client, &args.server, &args.uri, &args.file)
-                            {
-                                eprintln!("❌ Redeployment failed: {}", e);
+                                let client = client.clone();
+                                let args = Arc::clone(&args);
+                                let handle = tokio::spawn(async move {
+                                    println!("📝 File changed, redeploying...");
+                                    if let Err(e) =
+                                        deploy_with_build(&client, &args, &uri, &file_path).await
+                                    {
+                                        eprintln!("❌ Redeployment failed: {}", e);
+                                    }
+                                });
+                                in_flight.insert(file_path, handle);
                             }
                         }
                     }
-                    Err(e) => eprintln!("❌ Watch error: {:?}", e),
                 }
             }
-            Err(e) => {
-                eprintln!("❌ Channel error: {:?}", e);
-                break;
-            }
         }
     }
 